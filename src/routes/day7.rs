@@ -1,95 +1,209 @@
 use std::collections::HashMap;
+use std::future::{ready, Ready};
 
-use actix_web::{get, http::StatusCode, HttpRequest, HttpResponse, Responder, ResponseError};
+use actix_web::{
+    dev::Payload, get, http::StatusCode, post, web, FromRequest, HttpRequest, HttpResponse,
+    Responder, ResponseError,
+};
 use anyhow::Context;
 use base64::{engine::general_purpose, Engine as _};
-use serde::Serialize;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// An actix-web extractor that reads the `recipe` cookie, base64-decodes its value, and
+/// deserializes it into `T` as JSON. Centralizes the failure modes so any handler that needs
+/// a cookie-encoded payload can just ask for `Base64Cookie<T>` instead of hand-rolling it.
+pub struct Base64Cookie<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Base64Cookie<T> {
+    type Error = RecipeParseError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(decode_cookie(req, "recipe").map(Base64Cookie))
+    }
+}
+
+fn decode_cookie<T: DeserializeOwned>(
+    request: &HttpRequest,
+    name: &str,
+) -> Result<T, RecipeParseError> {
+    let cookie = request
+        .cookie(name)
+        .ok_or_else(|| RecipeParseError::MissingCookie(name.to_string()))?;
+
+    let decoded = general_purpose::STANDARD.decode(cookie.value())?;
+
+    Ok(serde_json::from_slice::<T>(&decoded)?)
+}
 
 #[tracing::instrument]
 #[get("/7/decode")]
-pub async fn decode(request: HttpRequest) -> Result<HttpResponse, RecipeParseError> {
-    let r = get_recipe_from_header(request).context("Error in recipe cookie")?;
-    tracing::debug!("Recipe: {:?}", &r);
+pub async fn decode(Base64Cookie(recipe): Base64Cookie<serde_json::Value>) -> impl Responder {
+    tracing::debug!("Recipe: {:?}", &recipe);
 
-    Ok(HttpResponse::Ok().json(r))
+    HttpResponse::Ok().json(recipe)
 }
 
 #[tracing::instrument]
 #[get("/7/bake")]
-pub async fn bake(request: HttpRequest) -> impl Responder {
-    let r = match get_recipe_from_header(request) {
-        Ok(r) => r,
-        Err(e) => {
-            tracing::debug!("Error in recipe cookie: {}", e);
-            return HttpResponse::BadRequest().finish();
-        }
-    };
-    tracing::debug!("Recipe: {:?}", &r);
+pub async fn bake(Base64Cookie(bake): Base64Cookie<Bake>) -> impl Responder {
+    tracing::debug!("Bake created successfully: {:#?}", &bake);
 
-    let bakery = match split_recipe_from_pantry(r) {
-        Ok(b) => b,
-        Err(e) => {
-            tracing::debug!("Error creating bakery: {}", e);
-            return HttpResponse::BadRequest().finish();
-        }
-    };
-    tracing::debug!("Bakery created successfully: {:#?}", &bakery);
-
-    let result = calculate_cookies(bakery);
+    let result = calculate_cookies(bake);
     tracing::debug!("Cookies and remaining pantry: {:#?}", &result);
     HttpResponse::Ok().json(result)
 }
 
-#[derive(Debug)]
-struct Bakery {
-    recipe: HashMap<String, u64>,
-    pantry: HashMap<String, u64>,
+#[tracing::instrument]
+#[get("/7/optimize")]
+pub async fn optimize(
+    Base64Cookie(input): Base64Cookie<OptimizeInput>,
+) -> Result<HttpResponse, RecipeParseError> {
+    tracing::debug!("Optimize input: {:?}", &input);
+
+    let result = optimize_cookie(input);
+    tracing::debug!("Optimize result: {:#?}", &result);
+
+    Ok(HttpResponse::Ok().json(result))
 }
 
-impl Default for Bakery {
-    fn default() -> Self {
-        Bakery {
-            recipe: HashMap::new(),
-            pantry: HashMap::new(),
-        }
-    }
+#[derive(Debug, Deserialize)]
+struct Ingredient {
+    capacity: i64,
+    durability: i64,
+    flavor: i64,
+    texture: i64,
+    calories: i64,
+}
+
+fn default_teaspoons() -> u64 {
+    100
+}
+
+#[derive(Debug, Deserialize)]
+struct OptimizeInput {
+    ingredients: HashMap<String, Ingredient>,
+    #[serde(default = "default_teaspoons")]
+    teaspoons: u64,
+    calories: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+struct OptimizeReply {
+    score: u64,
+    amounts: HashMap<String, u64>,
 }
 
 #[tracing::instrument]
-fn split_recipe_from_pantry(input: serde_json::Value) -> Result<Bakery, RecipeParseError> {
-    let mut bakery = Bakery::default();
+fn optimize_cookie(input: OptimizeInput) -> OptimizeReply {
+    let names: Vec<String> = input.ingredients.keys().cloned().collect();
+    let ingredients: Vec<&Ingredient> = names.iter().map(|name| &input.ingredients[name]).collect();
+
+    let mut best_score = 0u64;
+    let mut best_amounts = vec![0u64; names.len()];
+
+    if !ingredients.is_empty() {
+        let mut amounts = vec![0u64; names.len()];
+        search_compositions(
+            &ingredients,
+            input.teaspoons,
+            input.calories,
+            0,
+            &mut amounts,
+            &mut best_score,
+            &mut best_amounts,
+        );
+    }
+
+    let amounts = names.into_iter().zip(best_amounts).collect();
+
+    OptimizeReply {
+        score: best_score,
+        amounts,
+    }
+}
 
-    let recipe = input
-        .get("recipe")
-        .context("Unable to find recipe in the input")?
-        .to_owned();
+/// Recursively generates every integer composition of `remaining` teaspoons across the
+/// ingredients from `index` onward, scoring each leaf composition against the running best.
+fn search_compositions(
+    ingredients: &[&Ingredient],
+    remaining: u64,
+    calorie_target: Option<i64>,
+    index: usize,
+    amounts: &mut Vec<u64>,
+    best_score: &mut u64,
+    best_amounts: &mut Vec<u64>,
+) {
+    if index == ingredients.len() - 1 {
+        amounts[index] = remaining;
+        score_composition(
+            ingredients,
+            amounts,
+            calorie_target,
+            best_score,
+            best_amounts,
+        );
+        return;
+    }
 
-    let recipe = recipe
-        .as_object()
-        .context("Unable to get recipe as object")?;
+    for amount in 0..=remaining {
+        amounts[index] = amount;
+        search_compositions(
+            ingredients,
+            remaining - amount,
+            calorie_target,
+            index + 1,
+            amounts,
+            best_score,
+            best_amounts,
+        );
+    }
+}
 
-    for (key, value) in recipe.iter() {
-        bakery
-            .recipe
-            .insert(key.clone(), value.as_u64().unwrap_or(0));
+fn score_composition(
+    ingredients: &[&Ingredient],
+    amounts: &[u64],
+    calorie_target: Option<i64>,
+    best_score: &mut u64,
+    best_amounts: &mut Vec<u64>,
+) {
+    if let Some(target) = calorie_target {
+        let calories: i64 = ingredients
+            .iter()
+            .zip(amounts)
+            .map(|(ingredient, &amount)| ingredient.calories * amount as i64)
+            .sum();
+        if calories != target {
+            return;
+        }
     }
 
-    let pantry = input
-        .get("pantry")
-        .context("Unable to find pantry in input")?
-        .to_owned();
+    let sum_property = |property: fn(&Ingredient) -> i64| -> i64 {
+        ingredients
+            .iter()
+            .zip(amounts)
+            .map(|(ingredient, &amount)| property(ingredient) * amount as i64)
+            .sum::<i64>()
+            .max(0)
+    };
+
+    let capacity = sum_property(|i| i.capacity);
+    let durability = sum_property(|i| i.durability);
+    let flavor = sum_property(|i| i.flavor);
+    let texture = sum_property(|i| i.texture);
 
-    let pantry = pantry
-        .as_object()
-        .context("Unable to get recipe as object")?;
+    let score = (capacity * durability * flavor * texture) as u64;
 
-    for (key, value) in pantry.iter() {
-        bakery
-            .pantry
-            .insert(key.clone(), value.as_u64().unwrap_or(0));
+    if score > *best_score {
+        *best_score = score;
+        *best_amounts = amounts.to_vec();
     }
+}
 
-    Ok(bakery)
+#[derive(Debug, Default, Deserialize)]
+struct Bake {
+    recipe: HashMap<String, u64>,
+    pantry: HashMap<String, u64>,
 }
 
 #[derive(Debug, Serialize)]
@@ -99,19 +213,26 @@ struct BakeReply {
 }
 
 #[tracing::instrument]
-fn calculate_cookies(bakery: Bakery) -> BakeReply {
-    let mut remaining_pantry = HashMap::<String, u64>::new();
+fn calculate_cookies(bake: Bake) -> BakeReply {
+    // An ingredient the recipe needs zero of can never limit the batch, and dividing by it
+    // would panic, so drop those entries before computing how many cookies we can bake.
+    let recipe: HashMap<String, u64> = bake
+        .recipe
+        .into_iter()
+        .filter(|(_, recipe_amount)| *recipe_amount != 0)
+        .collect();
+
     let mut max_cookies_by_ingredient = Vec::<u64>::new();
 
-    for (ingredient, &recipe_amount) in bakery.recipe.iter() {
+    for (ingredient, &recipe_amount) in recipe.iter() {
         // Loop gets the amount the recipe needs
         // Next, get the amount in the pantry
-        let Some(&pantry_amount) = bakery.pantry.get(ingredient) else {
+        let Some(&pantry_amount) = bake.pantry.get(ingredient) else {
             // None of this ingredient. Push a zero to the counter collector
             max_cookies_by_ingredient.push(0);
             return BakeReply {
                 cookies: 0,
-                pantry: bakery.pantry,
+                pantry: bake.pantry,
             };
         };
 
@@ -120,7 +241,7 @@ fn calculate_cookies(bakery: Bakery) -> BakeReply {
             max_cookies_by_ingredient.push(0);
             return BakeReply {
                 cookies: 0,
-                pantry: bakery.pantry,
+                pantry: bake.pantry,
             };
         }
 
@@ -135,14 +256,13 @@ fn calculate_cookies(bakery: Bakery) -> BakeReply {
     }
     let cookies_can_be_baked = *max_cookies_by_ingredient.iter().min().unwrap_or(&0);
 
-    for (ingredient, &recipe_amount) in bakery.recipe.iter() {
-        // Loop gets the amount the recipe needs
-        let pantry_amount = bakery.pantry[ingredient];
-
-        remaining_pantry.insert(
-            ingredient.clone(),
-            pantry_amount - (recipe_amount * cookies_can_be_baked),
-        );
+    // Start from the whole pantry, not just the recipe's keys, so ingredients the recipe
+    // doesn't mention are still echoed back in the reply.
+    let mut remaining_pantry = bake.pantry;
+    for (ingredient, recipe_amount) in recipe.iter() {
+        if let Some(pantry_amount) = remaining_pantry.get_mut(ingredient) {
+            *pantry_amount -= recipe_amount * cookies_can_be_baked;
+        }
     }
 
     BakeReply {
@@ -152,36 +272,145 @@ fn calculate_cookies(bakery: Bakery) -> BakeReply {
 }
 
 #[tracing::instrument]
-fn get_recipe_from_header(request: HttpRequest) -> Result<serde_json::Value, RecipeParseError> {
+#[post("/7/parse")]
+pub async fn parse(body: web::Bytes, request: HttpRequest) -> Result<HttpResponse, RecipeParseError> {
+    let text = if body.is_empty() {
+        get_text_from_header(request).context("Error in recipe cookie")?
+    } else {
+        String::from_utf8(body.to_vec()).context("Request body is not valid UTF-8")?
+    };
+    tracing::debug!("Ingredient text: {:?}", &text);
+
+    let ingredients = parse_ingredients(&text);
+    tracing::debug!("Parsed ingredients: {:#?}", &ingredients);
+
+    Ok(HttpResponse::Ok().json(ingredients))
+}
+
+#[derive(Debug, Serialize)]
+struct ParsedIngredient {
+    name: String,
+    #[serde(serialize_with = "serialize_quantity")]
+    quantity: f32,
+    unit: Option<String>,
+}
+
+/// Emits whole quantities as bare integers (`135`) and only falls back to a float
+/// representation for fractional amounts (`4.75`), matching how recipe text is written.
+fn serialize_quantity<S>(quantity: &f32, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    if quantity.fract() == 0.0 {
+        serializer.serialize_i64(*quantity as i64)
+    } else {
+        serializer.serialize_f32(*quantity)
+    }
+}
+
+const KNOWN_UNITS: &[&str] = &[
+    "g", "kg", "oz", "lb", "ml", "l", "tsp", "tbsp", "cup", "cups", "large", "small", "medium",
+];
+
+fn parse_ingredients(text: &str) -> Vec<ParsedIngredient> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(parse_ingredient)
+        .collect()
+}
+
+fn parse_ingredient(item: &str) -> ParsedIngredient {
+    // Drop trailing qualifiers such as "egg (beaten)".
+    let item = item.split('(').next().unwrap_or(item).trim();
+
+    let mut words = item.split_whitespace();
+
+    let (quantity, glued_unit, rest): (f32, &str, Vec<&str>) =
+        match words.next().and_then(split_leading_quantity) {
+            Some((quantity, suffix)) => (quantity, suffix, words.collect()),
+            None => (1.0, "", item.split_whitespace().collect()),
+        };
+
+    let (unit, name_words): (Option<String>, Vec<&str>) = if KNOWN_UNITS.contains(&glued_unit) {
+        // The unit was glued directly onto the quantity, e.g. "135g".
+        (Some(glued_unit.to_string()), rest)
+    } else {
+        match rest.first() {
+            Some(&word) if KNOWN_UNITS.contains(&word) => {
+                (Some(word.to_string()), rest[1..].to_vec())
+            }
+            _ => (None, rest),
+        }
+    };
+
+    ParsedIngredient {
+        name: name_words.join(" "),
+        quantity,
+        unit,
+    }
+}
+
+/// Splits a token into its leading quantity (digits and/or a fraction glyph) and whatever
+/// follows, so a glued quantity+unit token like `"135g"` yields `(135.0, "g")`.
+fn split_leading_quantity(token: &str) -> Option<(f32, &str)> {
+    let end = token
+        .char_indices()
+        .find(|(_, c)| !(c.is_ascii_digit() || *c == '.' || matches!(c, '¼' | '½' | '¾')))
+        .map_or(token.len(), |(idx, _)| idx);
+
+    if end == 0 {
+        return None;
+    }
+
+    let (number, suffix) = token.split_at(end);
+    parse_quantity(number).map(|quantity| (quantity, suffix))
+}
+
+/// Parses a quantity string, including simple fractions like `½` and mixed numbers like `4¾`.
+fn parse_quantity(token: &str) -> Option<f32> {
+    const FRACTIONS: [(char, f32); 3] = [('¼', 0.25), ('½', 0.5), ('¾', 0.75)];
+
+    if let Some(&(symbol, fraction)) = FRACTIONS.iter().find(|(symbol, _)| token.ends_with(*symbol)) {
+        let whole_part = &token[..token.len() - symbol.len_utf8()];
+        let whole: f32 = if whole_part.is_empty() {
+            0.0
+        } else {
+            whole_part.parse().ok()?
+        };
+        return Some(whole + fraction);
+    }
+
+    token.parse::<f32>().ok()
+}
+
+#[tracing::instrument]
+fn get_text_from_header(request: HttpRequest) -> Result<String, RecipeParseError> {
     let recipe_cookie = request
         .cookie("recipe")
         .context("No cookie recipe in cookie jar")?;
 
     let recipe = recipe_cookie.to_string();
-    tracing::trace!("ToString: {:#?}", &recipe);
-
     let (_, recipe) = recipe
         .split_once("=")
         .context("Badly formed recipe cookie")?;
-    tracing::trace!("Split: {:#?}", &recipe);
 
     let recipe = general_purpose::STANDARD
         .decode(recipe)
         .context("Unable to base64 decode the cookie.")?;
-    tracing::trace!("base64 decode: {:#?}", &recipe);
 
-    let recipe =
-        serde_json::from_slice::<serde_json::Value>(&recipe).context("Unable to parse to JSON")?;
-    tracing::trace!("Json: {:#?}", &recipe);
-
-    Ok(recipe)
+    String::from_utf8(recipe).context("Cookie content is not valid UTF-8")
 }
 
 #[derive(thiserror::Error)]
 pub enum RecipeParseError {
+    #[error("No cookie named '{0}' in cookie jar")]
+    MissingCookie(String),
     #[error(transparent)]
     DecodeError(#[from] base64::DecodeError),
     #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
+    #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
 
@@ -194,7 +423,9 @@ impl std::fmt::Debug for RecipeParseError {
 impl ResponseError for RecipeParseError {
     fn status_code(&self) -> StatusCode {
         match self {
+            RecipeParseError::MissingCookie(_) => StatusCode::BAD_REQUEST,
             RecipeParseError::DecodeError(_) => StatusCode::BAD_REQUEST,
+            RecipeParseError::JsonError(_) => StatusCode::BAD_REQUEST,
             RecipeParseError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }